@@ -1,29 +1,120 @@
+use std::collections::HashMap;
 use std::io;
 use std::io::Read;
 use std::io::Write;
+use std::net::SocketAddr;
 use std::net::ToSocketAddrs;
 use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
 
 use failure::err_msg;
 use failure::Error;
+use failure::Fail;
 use mio::tcp::TcpStream;
 use rustls::Session;
 use url::Url;
 use vecio::Rawv;
 
+pub mod oneshot_tls;
+pub mod server;
+
 const CLIENT: mio::Token = mio::Token(0);
+const MAX_REDIRECTS: u32 = 10;
+
+/// A parsed HTTP response: status code, headers keyed by lower-cased name, and body bytes
+/// with any `Transfer-Encoding: chunked` framing already stripped.
+pub struct Response {
+    pub status: u16,
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+/// Deadlines applied to a request. `connect` bounds the wait for the first readiness event
+/// on the socket (for a fresh connection, the TCP connect completing); `idle` bounds the gap
+/// between successive readiness events after that. `None` disables the corresponding
+/// deadline and blocks as before.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Timeouts {
+    pub connect: Option<Duration>,
+    pub idle: Option<Duration>,
+}
+
+/// Returned when a `connect` or `idle` deadline in [`Timeouts`] is exceeded.
+#[derive(Debug, Fail)]
+#[fail(display = "timed out waiting for a response")]
+pub struct TimeoutError;
+
+/// Per-request options: whether to advertise and undo compression, the deadlines to apply
+/// to the underlying poll loop, and how many `3xx` redirect hops [`get`] will follow.
+#[derive(Clone, Copy, Debug)]
+pub struct RequestOptions {
+    pub decode: bool,
+    pub timeouts: Timeouts,
+    pub max_redirects: u32,
+}
+
+impl Default for RequestOptions {
+    fn default() -> RequestOptions {
+        RequestOptions {
+            decode: false,
+            timeouts: Timeouts::default(),
+            max_redirects: MAX_REDIRECTS,
+        }
+    }
+}
+
+/// Fetch `url`, following any `3xx` redirects up to `opts.max_redirects` hops (`MAX_REDIRECTS`
+/// by default). Any `Content-Encoding` of the response body is left as the server sent it;
+/// use [`get_decoded`] to transparently undo it.
+pub fn get<S: AsRef<str>>(url: S) -> Result<Response, Error> {
+    get_with_options(url, &RequestOptions::default())
+}
 
-pub fn get<S: AsRef<str>>(url: S) -> Result<(), Error> {
-    unimplemented!()
+/// As [`get`], but advertises `Accept-Encoding: gzip, deflate, br` and transparently decodes
+/// a compressed response body before returning it.
+pub fn get_decoded<S: AsRef<str>>(url: S) -> Result<Response, Error> {
+    get_with_options(
+        url,
+        &RequestOptions {
+            decode: true,
+            ..RequestOptions::default()
+        },
+    )
 }
 
-struct TlsClient {
+/// As [`get`], with full control over decoding, timeouts, and the redirect limit via `opts`.
+pub fn get_with_options<S: AsRef<str>>(url: S, opts: &RequestOptions) -> Result<Response, Error> {
+    let mut url = Url::parse(url.as_ref())?;
+
+    for _ in 0..=opts.max_redirects {
+        let resp = single_with_options(&url, opts)?;
+
+        if resp.status < 300 || resp.status >= 400 {
+            return Ok(resp);
+        }
+
+        let location = resp
+            .headers
+            .get("location")
+            .ok_or_else(|| err_msg("redirect response had no Location header"))?;
+        url = url.join(location)?;
+    }
+
+    Err(err_msg("too many redirects"))
+}
+
+/// A `mio`-driven TLS client connection: a raw `TcpStream` plus the `rustls::ClientSession`
+/// wrapping it. Shared with [`crate::oneshot_tls`], which drives the exact same non-blocking
+/// read/write/register dance against a one-shot request instead of [`Client`]'s persistent
+/// connection.
+pub(crate) struct TlsClient {
     socket: TcpStream,
-    tls_session: rustls::ClientSession,
+    pub(crate) tls_session: rustls::ClientSession,
 }
 
 impl TlsClient {
-    fn new(
+    pub(crate) fn new(
         sock: TcpStream,
         hostname: webpki::DNSNameRef<'_>,
         cfg: Arc<rustls::ClientConfig>,
@@ -34,7 +125,7 @@ impl TlsClient {
         }
     }
 
-    fn do_read(&mut self) -> Result<bool, Error> {
+    pub(crate) fn do_read(&mut self) -> Result<bool, Error> {
         // Read TLS data. This fails if the underlying TCP connection is broken.
 
         if 0 == self.tls_session.read_tls(&mut self.socket)? {
@@ -49,13 +140,13 @@ impl TlsClient {
         Ok(false)
     }
 
-    fn do_write(&mut self) {
+    pub(crate) fn do_write(&mut self) {
         self.tls_session
             .writev_tls(&mut WriteVAdapter::new(&mut self.socket))
             .unwrap();
     }
 
-    fn register(&self, poll: &mut mio::Poll) {
+    pub(crate) fn register(&self, poll: &mut mio::Poll) {
         poll.register(
             &self.socket,
             CLIENT,
@@ -65,7 +156,7 @@ impl TlsClient {
         .unwrap();
     }
 
-    fn reregister(&self, poll: &mut mio::Poll) {
+    pub(crate) fn reregister(&self, poll: &mut mio::Poll) {
         poll.reregister(
             &self.socket,
             CLIENT,
@@ -106,7 +197,29 @@ impl io::Read for TlsClient {
     }
 }
 
-pub fn single(url: &Url) -> Result<(), Error> {
+/// Issue a single `GET` against `url`, with no redirect handling. The connection is dialed
+/// and verified against `url`'s own host; use [`single_with_addr`] to dial a different
+/// address while still presenting and checking `url`'s host. Any `Content-Encoding` of the
+/// response body is left as the server sent it; use [`single_decoded`] to transparently
+/// undo it.
+pub fn single(url: &Url) -> Result<Response, Error> {
+    single_with_options(url, &RequestOptions::default())
+}
+
+/// As [`single`], but advertises `Accept-Encoding: gzip, deflate, br` and transparently
+/// decodes a compressed response body before returning it.
+pub fn single_decoded(url: &Url) -> Result<Response, Error> {
+    single_with_options(
+        url,
+        &RequestOptions {
+            decode: true,
+            ..RequestOptions::default()
+        },
+    )
+}
+
+/// As [`single`], with full control over decoding and timeouts via `opts`.
+pub fn single_with_options(url: &Url, opts: &RequestOptions) -> Result<Response, Error> {
     let host = url.host_str().ok_or_else(|| err_msg("relative url"))?;
 
     let port = url
@@ -118,6 +231,25 @@ pub fn single(url: &Url) -> Result<(), Error> {
         .next()
         .ok_or_else(|| err_msg("resolution empty"))?;
 
+    single_with_addr_options(addr, host, url, opts)
+}
+
+/// Issue a single `GET` against `url`, dialing `addr` directly rather than resolving
+/// `url`'s host, while still presenting `sni` for TLS SNI and certificate verification.
+/// Useful when the caller already has a `SocketAddr` in hand (their own resolver, a
+/// load-balancer VIP, split-horizon DNS) but still needs the certificate checked against
+/// the real domain. Any `Content-Encoding` of the response body is left as the server sent it.
+pub fn single_with_addr(addr: SocketAddr, sni: &str, url: &Url) -> Result<Response, Error> {
+    single_with_addr_options(addr, sni, url, &RequestOptions::default())
+}
+
+/// As [`single_with_addr`], with full control over decoding and timeouts via `opts`.
+pub fn single_with_addr_options(
+    addr: SocketAddr,
+    sni: &str,
+    url: &Url,
+    opts: &RequestOptions,
+) -> Result<Response, Error> {
     let sock = TcpStream::connect(&addr)?;
 
     let mut config = rustls::ClientConfig::new();
@@ -128,13 +260,23 @@ pub fn single(url: &Url) -> Result<(), Error> {
 
     let config = Arc::new(config);
     let dns_name =
-        webpki::DNSNameRef::try_from_ascii_str(host).map_err(|()| err_msg("invalid sni name"))?;
+        webpki::DNSNameRef::try_from_ascii_str(sni).map_err(|()| err_msg("invalid sni name"))?;
     let mut tlsclient = TlsClient::new(sock, dns_name, config);
 
+    let mut path = url.path().to_string();
+    if let Some(query) = url.query() {
+        path.push('?');
+        path.push_str(query);
+    }
+
+    let accept_encoding = if opts.decode {
+        "gzip, deflate, br"
+    } else {
+        "identity"
+    };
     let httpreq = format!(
-        "GET / HTTP/1.1\r\nHost: {}\r\nConnection: \
-         close\r\nAccept-Encoding: identity\r\n\r\n",
-        host
+        "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nAccept-Encoding: {}\r\n\r\n",
+        path, sni, accept_encoding
     );
     tlsclient.write_all(httpreq.as_bytes()).unwrap();
 
@@ -142,8 +284,32 @@ pub fn single(url: &Url) -> Result<(), Error> {
     let mut events = mio::Events::with_capacity(8);
     tlsclient.register(&mut poll);
 
+    let mut raw = Vec::new();
+    let mut connected = false;
+    let mut last_activity = Instant::now();
+
     'polling: loop {
-        poll.poll(&mut events, None)?;
+        let deadline = if connected {
+            opts.timeouts.idle
+        } else {
+            opts.timeouts.connect
+        };
+        let remaining =
+            deadline.map(|d| d.checked_sub(last_activity.elapsed()).unwrap_or_default());
+
+        poll.poll(&mut events, remaining)?;
+
+        if events.is_empty() {
+            if let Some(d) = deadline {
+                if last_activity.elapsed() >= d {
+                    return Err(TimeoutError.into());
+                }
+            }
+            continue;
+        }
+
+        connected = true;
+        last_activity = Instant::now();
 
         for ev in events.iter() {
             if ev.readiness().is_readable() {
@@ -154,7 +320,12 @@ pub fn single(url: &Url) -> Result<(), Error> {
                 let mut plaintext = Vec::new();
                 let rc = tlsclient.tls_session.read_to_end(&mut plaintext);
                 if !plaintext.is_empty() {
-                    io::stdout().write_all(&plaintext).unwrap();
+                    raw.extend_from_slice(&plaintext);
+                }
+
+                if let Some(end) = response_complete(&raw) {
+                    raw.truncate(end);
+                    break 'polling;
                 }
 
                 // If that fails, the peer might have started a clean TLS-level session closure.
@@ -177,7 +348,345 @@ pub fn single(url: &Url) -> Result<(), Error> {
 
     drop(poll);
 
-    Ok(())
+    parse_response(&raw, opts.decode)
+}
+
+/// A persistent handle to one host: keeps the `TcpStream`, `rustls::ClientSession` and
+/// `mio::Poll` alive across requests so repeated calls share one TLS session instead of
+/// paying for a fresh handshake every time. Sends `Connection: keep-alive` and stops
+/// reading at the parsed body boundary, so the socket is left usable for the next request.
+/// If a response says `Connection: close`, or the peer closes the connection while a
+/// response is being read, the next call to [`Client::request`] transparently reconnects,
+/// reusing the same `ClientConfig` (and so its session cache) in the hope of resuming the old
+/// session instead of a full handshake. A close that happens between requests is not
+/// detected beforehand: the request sent on that connection fails and is not retried.
+pub struct Client {
+    addr: SocketAddr,
+    sni: String,
+    config: Arc<rustls::ClientConfig>,
+    opts: RequestOptions,
+    tls: TlsClient,
+    poll: mio::Poll,
+    closed: bool,
+}
+
+impl Client {
+    /// Dial `addr`, presenting and verifying `sni`, ready to serve repeated requests.
+    pub fn connect(addr: SocketAddr, sni: &str, opts: RequestOptions) -> Result<Client, Error> {
+        let mut config = rustls::ClientConfig::new();
+        config
+            .root_store
+            .add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+        config.ct_logs = Some(&ct_logs::LOGS);
+
+        Client::connect_with_config(addr, sni, opts, Arc::new(config))
+    }
+
+    fn connect_with_config(
+        addr: SocketAddr,
+        sni: &str,
+        opts: RequestOptions,
+        config: Arc<rustls::ClientConfig>,
+    ) -> Result<Client, Error> {
+        let (tls, poll) = Client::dial(addr, sni, config.clone())?;
+
+        Ok(Client {
+            addr,
+            sni: sni.to_string(),
+            config,
+            opts,
+            tls,
+            poll,
+            closed: false,
+        })
+    }
+
+    fn dial(
+        addr: SocketAddr,
+        sni: &str,
+        config: Arc<rustls::ClientConfig>,
+    ) -> Result<(TlsClient, mio::Poll), Error> {
+        let sock = TcpStream::connect(&addr)?;
+        let dns_name = webpki::DNSNameRef::try_from_ascii_str(sni)
+            .map_err(|()| err_msg("invalid sni name"))?;
+        let tls = TlsClient::new(sock, dns_name, config);
+
+        let mut poll = mio::Poll::new()?;
+        tls.register(&mut poll);
+
+        Ok((tls, poll))
+    }
+
+    /// Issue a `GET` for `url`'s path on this session, reconnecting first if the previous
+    /// request (or a server-initiated close) left the connection unusable.
+    pub fn request(&mut self, url: &Url) -> Result<Response, Error> {
+        if self.closed {
+            let (tls, poll) = Client::dial(self.addr, &self.sni, self.config.clone())?;
+            self.tls = tls;
+            self.poll = poll;
+            self.closed = false;
+        }
+
+        let mut path = url.path().to_string();
+        if let Some(query) = url.query() {
+            path.push('?');
+            path.push_str(query);
+        }
+
+        let accept_encoding = if self.opts.decode {
+            "gzip, deflate, br"
+        } else {
+            "identity"
+        };
+        let httpreq = format!(
+            "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: keep-alive\r\nAccept-Encoding: {}\r\n\r\n",
+            path, self.sni, accept_encoding
+        );
+        self.tls.write_all(httpreq.as_bytes()).unwrap();
+
+        let mut raw = Vec::new();
+        let mut events = mio::Events::with_capacity(8);
+        let mut connected = false;
+        let mut last_activity = Instant::now();
+
+        'polling: loop {
+            let deadline = if connected {
+                self.opts.timeouts.idle
+            } else {
+                self.opts.timeouts.connect
+            };
+            let remaining =
+                deadline.map(|d| d.checked_sub(last_activity.elapsed()).unwrap_or_default());
+
+            self.poll.poll(&mut events, remaining)?;
+
+            if events.is_empty() {
+                if let Some(d) = deadline {
+                    if last_activity.elapsed() >= d {
+                        return Err(TimeoutError.into());
+                    }
+                }
+                continue;
+            }
+
+            connected = true;
+            last_activity = Instant::now();
+
+            for ev in events.iter() {
+                if ev.readiness().is_readable() {
+                    if self.tls.do_read()? {
+                        self.closed = true;
+                        self.tls.reregister(&mut self.poll);
+                        break 'polling;
+                    }
+
+                    let mut plaintext = Vec::new();
+                    let rc = self.tls.tls_session.read_to_end(&mut plaintext);
+                    if !plaintext.is_empty() {
+                        raw.extend_from_slice(&plaintext);
+                    }
+
+                    if let Some(end) = response_complete(&raw) {
+                        raw.truncate(end);
+                        self.tls.reregister(&mut self.poll);
+                        break 'polling;
+                    }
+
+                    if let Err(err) = rc {
+                        if io::ErrorKind::ConnectionAborted == err.kind() {
+                            self.closed = true;
+                            self.tls.reregister(&mut self.poll);
+                            break 'polling;
+                        }
+
+                        Err(err)?;
+                    }
+                }
+
+                if ev.readiness().is_writable() {
+                    self.tls.do_write();
+                }
+
+                self.tls.reregister(&mut self.poll);
+            }
+        }
+
+        let resp = parse_response(&raw, self.opts.decode)?;
+
+        if resp
+            .headers
+            .get("connection")
+            .map_or(false, |v| v.eq_ignore_ascii_case("close"))
+        {
+            self.closed = true;
+        }
+
+        Ok(resp)
+    }
+}
+
+/// Returns the length of the response at the start of `raw` once it is complete (headers plus a
+/// body sized per `Content-Length` or terminated by the final `chunked` trailer), so callers
+/// can stop reading without waiting for the peer to close the connection.
+pub(crate) fn response_complete(raw: &[u8]) -> Option<usize> {
+    let header_end = find_subslice(raw, b"\r\n\r\n")?;
+    let head = std::str::from_utf8(&raw[..header_end]).ok()?;
+    let body_start = header_end + 4;
+
+    let mut content_length = None;
+    let mut chunked = false;
+    for line in head.split("\r\n").skip(1) {
+        let idx = line.find(':')?;
+        let name = line[..idx].trim().to_lowercase();
+        let value = line[idx + 1..].trim();
+
+        if "content-length" == name {
+            content_length = value.parse::<usize>().ok();
+        } else if "transfer-encoding" == name && value.eq_ignore_ascii_case("chunked") {
+            chunked = true;
+        }
+    }
+
+    if chunked {
+        chunked_body_end(&raw[body_start..]).map(|len| body_start + len)
+    } else if let Some(len) = content_length {
+        if raw.len() >= body_start + len {
+            Some(body_start + len)
+        } else {
+            None
+        }
+    } else {
+        None
+    }
+}
+
+/// Scans a `Transfer-Encoding: chunked` body for its terminating zero-size chunk, parsing
+/// each chunk's size and skipping exactly that many bytes rather than scanning the whole
+/// buffer for a trailing marker — a binary chunk payload that happens to contain `0\r\n\r\n`
+/// partway through must not be mistaken for the end. Returns `None` if `buf` doesn't contain
+/// the whole body yet.
+fn chunked_body_end(buf: &[u8]) -> Option<usize> {
+    let mut pos = 0;
+
+    loop {
+        let line_end = pos + find_subslice(&buf[pos..], b"\r\n")?;
+        let size = usize::from_str_radix(std::str::from_utf8(&buf[pos..line_end]).ok()?.trim(), 16)
+            .ok()?;
+        let chunk_start = line_end + 2;
+
+        if 0 == size {
+            let trailer_end = chunk_start + 2;
+            return if buf.len() >= trailer_end {
+                Some(trailer_end)
+            } else {
+                None
+            };
+        }
+
+        let chunk_end = chunk_start + size + 2;
+        if buf.len() < chunk_end {
+            return None;
+        }
+        pos = chunk_end;
+    }
+}
+
+/// Split a complete, framed response buffer into its status, headers and (unchunked) body,
+/// transparently undoing `Content-Encoding` when `decode` is set.
+pub(crate) fn parse_response(raw: &[u8], decode: bool) -> Result<Response, Error> {
+    let header_end =
+        find_subslice(raw, b"\r\n\r\n").ok_or_else(|| err_msg("incomplete response headers"))?;
+    let head = std::str::from_utf8(&raw[..header_end])?;
+    let mut lines = head.split("\r\n");
+
+    let status_line = lines.next().ok_or_else(|| err_msg("empty response"))?;
+    let status = status_line
+        .splitn(3, ' ')
+        .nth(1)
+        .ok_or_else(|| err_msg("malformed status line"))?
+        .parse::<u16>()?;
+
+    let mut headers = HashMap::new();
+    for line in lines {
+        if let Some(idx) = line.find(':') {
+            headers.insert(
+                line[..idx].trim().to_lowercase(),
+                line[idx + 1..].trim().to_string(),
+            );
+        }
+    }
+
+    let body_start = header_end + 4;
+    let mut body = if headers
+        .get("transfer-encoding")
+        .map_or(false, |v| v.eq_ignore_ascii_case("chunked"))
+    {
+        dechunk(&raw[body_start..])?
+    } else {
+        raw[body_start..].to_vec()
+    };
+
+    if decode {
+        body = decode_body(&headers, body)?;
+    }
+
+    Ok(Response {
+        status,
+        headers,
+        body,
+    })
+}
+
+/// Undo `Content-Encoding` (`gzip`, `deflate` or `br`), falling back to the body unchanged
+/// when the header is absent or names an encoding we don't recognise.
+fn decode_body(headers: &HashMap<String, String>, body: Vec<u8>) -> Result<Vec<u8>, Error> {
+    let encoding = match headers.get("content-encoding") {
+        Some(encoding) => encoding.to_lowercase(),
+        None => return Ok(body),
+    };
+
+    let mut out = Vec::new();
+    match encoding.as_str() {
+        "gzip" => {
+            flate2::read::GzDecoder::new(&body[..]).read_to_end(&mut out)?;
+        }
+        "deflate" => {
+            flate2::read::DeflateDecoder::new(&body[..]).read_to_end(&mut out)?;
+        }
+        "br" => {
+            brotli::Decompressor::new(&body[..], 4096).read_to_end(&mut out)?;
+        }
+        _ => return Ok(body),
+    }
+
+    Ok(out)
+}
+
+/// Strip `Transfer-Encoding: chunked` framing, returning the concatenated chunk payloads.
+fn dechunk(mut buf: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut body = Vec::new();
+
+    loop {
+        let line_end =
+            find_subslice(buf, b"\r\n").ok_or_else(|| err_msg("truncated chunk size"))?;
+        let size = usize::from_str_radix(std::str::from_utf8(&buf[..line_end])?.trim(), 16)?;
+        buf = &buf[line_end + 2..];
+
+        if 0 == size {
+            break;
+        }
+
+        body.extend_from_slice(&buf[..size]);
+        buf = &buf[size + 2..];
+    }
+
+    Ok(body)
+}
+
+fn find_subslice(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
 }
 
 pub struct WriteVAdapter<'a> {
@@ -195,3 +704,207 @@ impl<'a> rustls::WriteV for WriteVAdapter<'a> {
         self.rawv.writev(bytes)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    // A throwaway self-signed `localhost` certificate, used only to stand up a local TLS
+    // server for tests; it is not, and does not need to be, trusted by anything else.
+    const TEST_CERT_PEM: &str = include_str!("../tests/fixtures/test_cert.pem");
+    const TEST_KEY_PEM: &str = include_str!("../tests/fixtures/test_key.pem");
+
+    fn test_client_config() -> Arc<rustls::ClientConfig> {
+        let mut config = rustls::ClientConfig::new();
+        let mut cert_reader = io::BufReader::new(TEST_CERT_PEM.as_bytes());
+        for cert in &rustls::internal::pemfile::certs(&mut cert_reader).unwrap() {
+            config.root_store.add(cert).unwrap();
+        }
+        Arc::new(config)
+    }
+
+    fn test_server_config() -> Arc<rustls::ServerConfig> {
+        let mut cert_reader = io::BufReader::new(TEST_CERT_PEM.as_bytes());
+        let certs = rustls::internal::pemfile::certs(&mut cert_reader).unwrap();
+
+        let mut key_reader = io::BufReader::new(TEST_KEY_PEM.as_bytes());
+        let mut keys = rustls::internal::pemfile::rsa_private_keys(&mut key_reader).unwrap();
+
+        let mut config = rustls::ServerConfig::new(rustls::NoClientAuth::new());
+        config.set_single_cert(certs, keys.remove(0)).unwrap();
+        Arc::new(config)
+    }
+
+    /// Reads off a single request's worth of bytes (up to the blank line ending its
+    /// headers) and returns them; every canned test server below ignores them, caring only
+    /// that a request was sent, not what it says.
+    fn read_request_headers(stream: &mut impl Read) -> Vec<u8> {
+        let mut request = Vec::new();
+        let mut buf = [0u8; 4096];
+        while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+            let n = stream.read(&mut buf).unwrap();
+            request.extend_from_slice(&buf[..n]);
+        }
+        request
+    }
+
+    /// Serves two fixed `Connection: keep-alive` HTTP responses on the one accepted
+    /// connection, so the test can assert that a single `Client` can issue a second
+    /// `request()` without reconnecting.
+    fn serve_two_responses_on_one_connection(
+        listener: TcpListener,
+        config: Arc<rustls::ServerConfig>,
+    ) {
+        let (mut sock, _) = listener.accept().unwrap();
+        let mut session = rustls::ServerSession::new(&config);
+        let mut stream = rustls::Stream::new(&mut session, &mut sock);
+
+        for body in &["first", "second"] {
+            read_request_headers(&mut stream);
+
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: keep-alive\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            stream.write_all(response.as_bytes()).unwrap();
+        }
+    }
+
+    /// Serves each of `responses` verbatim on its own freshly accepted connection, mirroring
+    /// how `single_with_options` dials a new connection per hop (it always sends
+    /// `Connection: close`, so there's no keep-alive to reuse between redirects).
+    fn serve_responses_on_separate_connections(
+        listener: TcpListener,
+        config: Arc<rustls::ServerConfig>,
+        responses: Vec<Vec<u8>>,
+    ) {
+        for response in responses {
+            let (mut sock, _) = listener.accept().unwrap();
+            let mut session = rustls::ServerSession::new(&config);
+            let mut stream = rustls::Stream::new(&mut session, &mut sock);
+            read_request_headers(&mut stream);
+            stream.write_all(&response).unwrap();
+        }
+    }
+
+    #[test]
+    fn client_reuses_connection_across_two_requests() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server_config = test_server_config();
+
+        let server =
+            thread::spawn(move || serve_two_responses_on_one_connection(listener, server_config));
+
+        let mut client = Client::connect_with_config(
+            addr,
+            "localhost",
+            RequestOptions::default(),
+            test_client_config(),
+        )
+        .unwrap();
+
+        let url = Url::parse("https://localhost/").unwrap();
+
+        let first = client.request(&url).unwrap();
+        assert_eq!(200, first.status);
+        assert_eq!(b"first", &first.body[..]);
+        assert!(!client.closed);
+
+        let second = client.request(&url).unwrap();
+        assert_eq!(200, second.status);
+        assert_eq!(b"second", &second.body[..]);
+
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn get_follows_redirect_to_final_response() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server_config = test_server_config();
+
+        let redirect =
+            b"HTTP/1.1 302 Found\r\nLocation: /final\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                .to_vec();
+        let final_body = "redirected";
+        let final_response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            final_body.len(),
+            final_body
+        )
+        .into_bytes();
+
+        let server = thread::spawn(move || {
+            serve_responses_on_separate_connections(
+                listener,
+                server_config,
+                vec![redirect, final_response],
+            )
+        });
+
+        let url = format!("https://localhost:{}/start", addr.port());
+        let resp = get(&url).unwrap();
+
+        assert_eq!(200, resp.status);
+        assert_eq!(b"redirected", &resp.body[..]);
+
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn single_with_addr_reads_chunked_body() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server_config = test_server_config();
+
+        let response =
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n"
+                .to_vec();
+
+        let server = thread::spawn(move || {
+            serve_responses_on_separate_connections(listener, server_config, vec![response])
+        });
+
+        let url = Url::parse("https://localhost/").unwrap();
+        let resp = single_with_addr(addr, "localhost", &url).unwrap();
+
+        assert_eq!(200, resp.status);
+        assert_eq!(b"hello world", &resp.body[..]);
+
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn single_decoded_ungzips_response_body() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server_config = test_server_config();
+
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(b"hello gzip").unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let mut response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Encoding: gzip\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            compressed.len()
+        )
+        .into_bytes();
+        response.extend_from_slice(&compressed);
+
+        let server = thread::spawn(move || {
+            serve_responses_on_separate_connections(listener, server_config, vec![response])
+        });
+
+        let url = Url::parse(&format!("https://localhost:{}/", addr.port())).unwrap();
+        let resp = single_decoded(&url).unwrap();
+
+        assert_eq!(200, resp.status);
+        assert_eq!(b"hello gzip", &resp.body[..]);
+
+        server.join().unwrap();
+    }
+}
@@ -1,22 +1,56 @@
 use std::fs::File;
 use std::io;
 use std::io::Read;
+use std::io::Seek;
 use std::io::Write;
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Instant;
 
 use failure::err_msg;
 use failure::Error;
 use mio::tcp::TcpStream;
-use rustls::Session;
-use vecio::Rawv;
 
-const CLIENT: mio::Token = mio::Token(0);
+use crate::parse_response;
+use crate::response_complete;
+use crate::RequestOptions;
+use crate::TimeoutError;
+use crate::TlsClient;
 
+/// Send `send` verbatim and write the response body to a tempfile, with any `chunked`
+/// framing stripped but its `Content-Encoding` left undecoded.
 pub fn oneshot<S: AsRef<str>, W: AsRef<[u8]>>(
     addr: SocketAddr,
     host: S,
     send: W,
+) -> Result<File, Error> {
+    oneshot_with_options(addr, host, send, &RequestOptions::default())
+}
+
+/// As [`oneshot`], but transparently decodes the response body per its `Content-Encoding`
+/// before writing it to the tempfile.
+pub fn oneshot_decoded<S: AsRef<str>, W: AsRef<[u8]>>(
+    addr: SocketAddr,
+    host: S,
+    send: W,
+) -> Result<File, Error> {
+    oneshot_with_options(
+        addr,
+        host,
+        send,
+        &RequestOptions {
+            decode: true,
+            ..RequestOptions::default()
+        },
+    )
+}
+
+/// As [`oneshot`], with full control over decoding and timeouts via `opts`.
+pub fn oneshot_with_options<S: AsRef<str>, W: AsRef<[u8]>>(
+    addr: SocketAddr,
+    host: S,
+    send: W,
+    opts: &RequestOptions,
 ) -> Result<File, Error> {
     let sock = TcpStream::connect(&addr)?;
 
@@ -37,137 +71,71 @@ pub fn oneshot<S: AsRef<str>, W: AsRef<[u8]>>(
     let mut events = mio::Events::with_capacity(8);
     tlsclient.register(&mut poll);
 
-    let mut out = tempfile::tempfile()?;
+    let mut raw = Vec::new();
+    let mut connected = false;
+    let mut last_activity = Instant::now();
 
     'polling: loop {
-        poll.poll(&mut events, None)?;
+        let deadline = if connected {
+            opts.timeouts.idle
+        } else {
+            opts.timeouts.connect
+        };
+        let remaining =
+            deadline.map(|d| d.checked_sub(last_activity.elapsed()).unwrap_or_default());
+
+        poll.poll(&mut events, remaining)?;
+
+        if events.is_empty() {
+            if let Some(d) = deadline {
+                if last_activity.elapsed() >= d {
+                    return Err(TimeoutError.into());
+                }
+            }
+            continue;
+        }
+
+        connected = true;
+        last_activity = Instant::now();
 
         for ev in events.iter() {
             if ev.readiness().is_readable() {
-                if tlsclient.maybe_read_packets()? {
-                    return Ok(out);
+                if tlsclient.do_read()? {
+                    break 'polling;
+                }
+
+                let mut plaintext = Vec::new();
+                let rc = tlsclient.tls_session.read_to_end(&mut plaintext);
+                if !plaintext.is_empty() {
+                    raw.extend_from_slice(&plaintext);
+                }
+
+                if let Some(end) = response_complete(&raw) {
+                    raw.truncate(end);
+                    break 'polling;
                 }
 
                 // If that fails, the peer might have started a clean TLS-level session closure.
-                match io::copy(&mut tlsclient.tls_session, &mut out) {
-                    Ok(_) => (),
-                    Err(ref err) if io::ErrorKind::ConnectionAborted == err.kind() => {
-                        return Ok(out);
+                if let Err(err) = rc {
+                    if io::ErrorKind::ConnectionAborted == err.kind() {
+                        break 'polling;
                     }
-                    Err(err) => Err(err)?,
+
+                    Err(err)?;
                 }
             }
 
             if ev.readiness().is_writable() {
-                tlsclient.maybe_write_packets();
+                tlsclient.do_write();
             }
 
             tlsclient.reregister(&mut poll);
         }
     }
-}
-
-struct TlsClient {
-    socket: TcpStream,
-    tls_session: rustls::ClientSession,
-}
-
-impl TlsClient {
-    fn new(
-        sock: TcpStream,
-        hostname: webpki::DNSNameRef<'_>,
-        cfg: Arc<rustls::ClientConfig>,
-    ) -> TlsClient {
-        TlsClient {
-            socket: sock,
-            tls_session: rustls::ClientSession::new(&cfg, hostname),
-        }
-    }
-
-    fn maybe_read_packets(&mut self) -> Result<bool, Error> {
-        // Read TLS data. This fails if the underlying TCP connection is broken.
 
-        if 0 == self.tls_session.read_tls(&mut self.socket)? {
-            // "clean eof".. not sure why we would get here without a close-notify
-            return Ok(true);
-        }
-
-        // Reading some TLS data might have yielded new TLS messages to process.
-        // Errors from this indicate TLS protocol problems and are fatal.
-        self.tls_session.process_new_packets()?;
-
-        Ok(false)
-    }
-
-    fn maybe_write_packets(&mut self) {
-        self.tls_session
-            .writev_tls(&mut WriteVAdapter::new(&mut self.socket))
-            .unwrap();
-    }
-
-    fn register(&self, poll: &mut mio::Poll) {
-        poll.register(
-            &self.socket,
-            CLIENT,
-            self.ready_interest(),
-            mio::PollOpt::level() | mio::PollOpt::oneshot(),
-        )
-        .unwrap();
-    }
-
-    fn reregister(&self, poll: &mut mio::Poll) {
-        poll.reregister(
-            &self.socket,
-            CLIENT,
-            self.ready_interest(),
-            mio::PollOpt::level() | mio::PollOpt::oneshot(),
-        )
-        .unwrap();
-    }
-
-    // Use wants_read/wants_write to register for different mio-level IO readiness events.
-    fn ready_interest(&self) -> mio::Ready {
-        let rd = self.tls_session.wants_read();
-        let wr = self.tls_session.wants_write();
-
-        if rd && wr {
-            mio::Ready::readable() | mio::Ready::writable()
-        } else if wr {
-            mio::Ready::writable()
-        } else {
-            mio::Ready::readable()
-        }
-    }
-}
-
-impl io::Write for TlsClient {
-    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
-        self.tls_session.write(bytes)
-    }
-
-    fn flush(&mut self) -> io::Result<()> {
-        self.tls_session.flush()
-    }
-}
-
-impl io::Read for TlsClient {
-    fn read(&mut self, bytes: &mut [u8]) -> io::Result<usize> {
-        self.tls_session.read(bytes)
-    }
-}
-
-pub struct WriteVAdapter<'a> {
-    rawv: &'a mut dyn Rawv,
-}
-
-impl<'a> WriteVAdapter<'a> {
-    pub fn new(rawv: &'a mut dyn Rawv) -> WriteVAdapter<'a> {
-        WriteVAdapter { rawv }
-    }
-}
+    let mut out = tempfile::tempfile()?;
+    out.write_all(&parse_response(&raw, opts.decode)?.body)?;
+    out.seek(io::SeekFrom::Start(0))?;
 
-impl<'a> rustls::WriteV for WriteVAdapter<'a> {
-    fn writev(&mut self, bytes: &[&[u8]]) -> io::Result<usize> {
-        self.rawv.writev(bytes)
-    }
+    Ok(out)
 }
@@ -0,0 +1,497 @@
+use std::collections::HashMap;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::net::ToSocketAddrs;
+use std::sync::Arc;
+
+use failure::err_msg;
+use failure::Error;
+use mio::tcp::TcpListener;
+use mio::tcp::TcpStream;
+use rustls::Session;
+use vecio::Rawv;
+
+use crate::WriteVAdapter;
+
+const LISTENER: mio::Token = mio::Token(0);
+
+/// What a [`run`] loop does with the plaintext it receives on each accepted connection.
+pub enum ServerMode {
+    /// Write whatever plaintext is received straight back to the peer.
+    Echo,
+    /// Read once, then send a fixed minimal HTTP/1.1 response and close-notify.
+    Http,
+    /// Proxy plaintext bidirectionally to a plain TCP socket on `localhost:port`.
+    Forward(u16),
+}
+
+/// The non-blocking backend half of a `Forward` connection: a plain TCP socket to
+/// `localhost:port`, registered with the same `poll` as its owning `Connection` under its
+/// own token, so a backend that keeps the connection open can't stall the rest of the
+/// listener the way a blocking bridge would.
+struct Forward {
+    socket: TcpStream,
+    token: mio::Token,
+    pending: Vec<u8>,
+}
+
+impl Forward {
+    fn ready_interest(&self) -> mio::Ready {
+        if self.pending.is_empty() {
+            mio::Ready::readable()
+        } else {
+            mio::Ready::readable() | mio::Ready::writable()
+        }
+    }
+
+    fn register(&self, poll: &mut mio::Poll) {
+        poll.register(
+            &self.socket,
+            self.token,
+            self.ready_interest(),
+            mio::PollOpt::level() | mio::PollOpt::oneshot(),
+        )
+        .unwrap();
+    }
+
+    fn reregister(&self, poll: &mut mio::Poll) {
+        poll.reregister(
+            &self.socket,
+            self.token,
+            self.ready_interest(),
+            mio::PollOpt::level() | mio::PollOpt::oneshot(),
+        )
+        .unwrap();
+    }
+
+    /// Drain whatever the backend has waiting, non-blocking. `Ok(None)` means the backend
+    /// closed its end; bytes read before that close are returned first, and the close is
+    /// reported on the next call.
+    fn do_read(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        let mut out = Vec::new();
+        let mut buf = [0u8; 4096];
+        loop {
+            match self.socket.read(&mut buf) {
+                Ok(0) if out.is_empty() => return Ok(None),
+                Ok(0) => return Ok(Some(out)),
+                Ok(n) => out.extend_from_slice(&buf[..n]),
+                Err(ref err) if io::ErrorKind::WouldBlock == err.kind() => return Ok(Some(out)),
+                Err(err) => Err(err)?,
+            }
+        }
+    }
+
+    fn do_write(&mut self) -> Result<(), Error> {
+        while !self.pending.is_empty() {
+            match self.socket.write(&self.pending) {
+                Ok(n) => {
+                    self.pending.drain(..n);
+                }
+                Err(ref err) if io::ErrorKind::WouldBlock == err.kind() => break,
+                Err(err) => Err(err)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Connection {
+    socket: TcpStream,
+    tls_session: rustls::ServerSession,
+    forward: Option<Forward>,
+    closing: bool,
+}
+
+impl Connection {
+    fn new(socket: TcpStream, cfg: Arc<rustls::ServerConfig>) -> Connection {
+        Connection {
+            socket,
+            tls_session: rustls::ServerSession::new(&cfg),
+            forward: None,
+            closing: false,
+        }
+    }
+
+    fn do_read(&mut self) -> Result<(), Error> {
+        if 0 == self.tls_session.read_tls(&mut self.socket)? {
+            self.closing = true;
+            return Ok(());
+        }
+
+        self.tls_session.process_new_packets()?;
+
+        Ok(())
+    }
+
+    fn do_write(&mut self) {
+        self.tls_session
+            .writev_tls(&mut WriteVAdapter::new(&mut self.socket))
+            .unwrap();
+    }
+
+    fn register(&self, poll: &mut mio::Poll, token: mio::Token) {
+        poll.register(
+            &self.socket,
+            token,
+            self.ready_interest(),
+            mio::PollOpt::level() | mio::PollOpt::oneshot(),
+        )
+        .unwrap();
+    }
+
+    fn reregister(&self, poll: &mut mio::Poll, token: mio::Token) {
+        poll.reregister(
+            &self.socket,
+            token,
+            self.ready_interest(),
+            mio::PollOpt::level() | mio::PollOpt::oneshot(),
+        )
+        .unwrap();
+    }
+
+    fn ready_interest(&self) -> mio::Ready {
+        let rd = self.tls_session.wants_read();
+        let wr = self.tls_session.wants_write();
+
+        if rd && wr {
+            mio::Ready::readable() | mio::Ready::writable()
+        } else if wr {
+            mio::Ready::writable()
+        } else {
+            mio::Ready::readable()
+        }
+    }
+
+    /// A connection is done once it's been told to close *and* everything already queued for
+    /// the peer (e.g. a buffered `Http` response plus its close-notify) has actually been
+    /// flushed by `do_write` — otherwise a response written right before closing would never
+    /// reach the wire.
+    fn is_closed(&self) -> bool {
+        self.closing && !self.tls_session.wants_write()
+    }
+
+    /// Handle plaintext freshly decrypted from the peer. For `Forward`, this only dials the
+    /// backend (on the first call) and queues the bytes for it; `run` drives the backend
+    /// socket's own events, since it owns the token/poll bookkeeping shared across
+    /// connections.
+    fn handle_plaintext(
+        &mut self,
+        mode: &ServerMode,
+        poll: &mut mio::Poll,
+        next_token: &mut usize,
+    ) -> Result<(), Error> {
+        let mut plaintext = Vec::new();
+        self.tls_session.read_to_end(&mut plaintext).ok();
+
+        if plaintext.is_empty() {
+            return Ok(());
+        }
+
+        match mode {
+            ServerMode::Echo => {
+                self.tls_session.write_all(&plaintext)?;
+            }
+            ServerMode::Http => {
+                let response = b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+                self.tls_session.write_all(response)?;
+                self.tls_session.send_close_notify();
+                self.closing = true;
+            }
+            ServerMode::Forward(port) => {
+                if let Some(forward) = &mut self.forward {
+                    forward.pending.extend_from_slice(&plaintext);
+                    forward.reregister(poll);
+                } else {
+                    let addr = ("localhost", *port)
+                        .to_socket_addrs()?
+                        .next()
+                        .ok_or_else(|| err_msg("resolution empty"))?;
+
+                    let token = mio::Token(*next_token);
+                    *next_token += 1;
+
+                    let forward = Forward {
+                        socket: TcpStream::connect(&addr)?,
+                        token,
+                        pending: plaintext,
+                    };
+                    forward.register(poll);
+                    self.forward = Some(forward);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Accept TLS connections on `addr` and drive each one according to `mode`, until the
+/// process is killed. Mirrors the client's `mio` poll loop and `WriteVAdapter`, but on the
+/// accept side: a `TcpListener` feeds a `HashMap<mio::Token, Connection>` keyed by an
+/// incrementing token, and each connection is removed once its session closes. `Forward`'s
+/// backend socket gets its own token in `forwards`, which points back at its owning
+/// connection, so it's driven by the same non-blocking poll loop instead of a blocking
+/// bridge. A single connection's error (a failed handshake, a reset, a plaintext probe
+/// hitting the TLS port) is logged and that connection is dropped, rather than taking down
+/// the whole listener.
+pub fn run<A: ToSocketAddrs>(
+    addr: A,
+    cfg: Arc<rustls::ServerConfig>,
+    mode: ServerMode,
+) -> Result<(), Error> {
+    let addr = addr
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| err_msg("resolution empty"))?;
+
+    let listener = TcpListener::bind(&addr)?;
+
+    let mut poll = mio::Poll::new()?;
+    poll.register(
+        &listener,
+        LISTENER,
+        mio::Ready::readable(),
+        mio::PollOpt::level(),
+    )?;
+
+    let mut events = mio::Events::with_capacity(256);
+    let mut connections: HashMap<mio::Token, Connection> = HashMap::new();
+    let mut forwards: HashMap<mio::Token, mio::Token> = HashMap::new();
+    let mut next_token = 1;
+
+    loop {
+        poll.poll(&mut events, None)?;
+
+        for ev in events.iter() {
+            if LISTENER == ev.token() {
+                match listener.accept() {
+                    Ok((socket, _)) => {
+                        let token = mio::Token(next_token);
+                        next_token += 1;
+
+                        let conn = Connection::new(socket, cfg.clone());
+                        conn.register(&mut poll, token);
+                        connections.insert(token, conn);
+                    }
+                    Err(err) => eprintln!("retch: accept failed, dropping it: {}", err),
+                }
+                continue;
+            }
+
+            let token = ev.token();
+
+            if let Some(&owner) = forwards.get(&token) {
+                let done = match drive_forward(&mut connections, owner, &mut poll, ev) {
+                    Ok(done) => done,
+                    Err(err) => {
+                        eprintln!("retch: forward connection failed, dropping it: {}", err);
+                        true
+                    }
+                };
+
+                if done {
+                    forwards.remove(&token);
+                    if let Some(conn) = connections.get_mut(&owner) {
+                        conn.forward = None;
+                    }
+                }
+
+                continue;
+            }
+
+            let done = match drive_connection(
+                &mut connections,
+                token,
+                &mut poll,
+                ev,
+                &mode,
+                &mut next_token,
+            ) {
+                Ok(done) => done,
+                Err(err) => {
+                    eprintln!("retch: connection failed, dropping it: {}", err);
+                    true
+                }
+            };
+
+            if let Some(forward_token) = connections
+                .get(&token)
+                .and_then(|conn| conn.forward.as_ref())
+                .map(|forward| forward.token)
+            {
+                forwards.entry(forward_token).or_insert(token);
+            }
+
+            if done {
+                if let Some(conn) = connections.remove(&token) {
+                    if let Some(forward) = conn.forward {
+                        forwards.remove(&forward.token);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Drive one event for a client-facing `Connection`, returning whether it's now closed.
+fn drive_connection(
+    connections: &mut HashMap<mio::Token, Connection>,
+    token: mio::Token,
+    poll: &mut mio::Poll,
+    ev: mio::Event,
+    mode: &ServerMode,
+    next_token: &mut usize,
+) -> Result<bool, Error> {
+    let conn = match connections.get_mut(&token) {
+        Some(conn) => conn,
+        None => return Ok(false),
+    };
+
+    if ev.readiness().is_readable() {
+        conn.do_read()?;
+        conn.handle_plaintext(mode, poll, next_token)?;
+    }
+
+    if ev.readiness().is_writable() {
+        conn.do_write();
+    }
+
+    if !conn.is_closed() {
+        conn.reregister(poll, token);
+    }
+
+    Ok(conn.is_closed())
+}
+
+/// Drive one event for a `Forward` backend socket, shuttling bytes to/from its owning
+/// `Connection`'s TLS session. Returns whether the backend is now done (closed, or its owner
+/// already gone).
+fn drive_forward(
+    connections: &mut HashMap<mio::Token, Connection>,
+    owner: mio::Token,
+    poll: &mut mio::Poll,
+    ev: mio::Event,
+) -> Result<bool, Error> {
+    let conn = match connections.get_mut(&owner) {
+        Some(conn) => conn,
+        None => return Ok(true),
+    };
+    let forward = match &mut conn.forward {
+        Some(forward) => forward,
+        None => return Ok(true),
+    };
+
+    let mut backend_closed = false;
+
+    if ev.readiness().is_readable() {
+        match forward.do_read()? {
+            Some(bytes) => {
+                if !bytes.is_empty() {
+                    conn.tls_session.write_all(&bytes)?;
+                }
+            }
+            None => backend_closed = true,
+        }
+    }
+
+    if ev.readiness().is_writable() {
+        forward.do_write()?;
+    }
+
+    if backend_closed {
+        conn.tls_session.send_close_notify();
+        conn.closing = true;
+        conn.reregister(poll, owner);
+        return Ok(true);
+    }
+
+    conn.reregister(poll, owner);
+    conn.forward.as_ref().unwrap().reregister(poll);
+
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener as StdTcpListener;
+    use std::net::TcpStream as StdTcpStream;
+    use std::thread;
+    use std::time::Duration;
+
+    const TEST_CERT_PEM: &str = include_str!("../tests/fixtures/test_cert.pem");
+    const TEST_KEY_PEM: &str = include_str!("../tests/fixtures/test_key.pem");
+
+    fn test_server_config() -> Arc<rustls::ServerConfig> {
+        let mut cert_reader = io::BufReader::new(TEST_CERT_PEM.as_bytes());
+        let certs = rustls::internal::pemfile::certs(&mut cert_reader).unwrap();
+
+        let mut key_reader = io::BufReader::new(TEST_KEY_PEM.as_bytes());
+        let mut keys = rustls::internal::pemfile::rsa_private_keys(&mut key_reader).unwrap();
+
+        let mut config = rustls::ServerConfig::new(rustls::NoClientAuth::new());
+        config.set_single_cert(certs, keys.remove(0)).unwrap();
+        Arc::new(config)
+    }
+
+    fn test_client_config() -> Arc<rustls::ClientConfig> {
+        let mut config = rustls::ClientConfig::new();
+        let mut cert_reader = io::BufReader::new(TEST_CERT_PEM.as_bytes());
+        for cert in &rustls::internal::pemfile::certs(&mut cert_reader).unwrap() {
+            config.root_store.add(cert).unwrap();
+        }
+        Arc::new(config)
+    }
+
+    /// `Forward` is the one `ServerMode` with real non-blocking logic of its own (dialing the
+    /// backend, shuttling bytes both ways), so this dials a plaintext TCP echo server through
+    /// it and checks the bytes make the whole round trip.
+    #[test]
+    fn forward_proxies_bytes_to_backend_and_back() {
+        let backend = StdTcpListener::bind("127.0.0.1:0").unwrap();
+        let backend_port = backend.local_addr().unwrap().port();
+        let backend_thread = thread::spawn(move || {
+            let (mut sock, _) = backend.accept().unwrap();
+            let mut buf = [0u8; 4096];
+            let n = sock.read(&mut buf).unwrap();
+            sock.write_all(&buf[..n]).unwrap();
+        });
+
+        // Grab a free port, then let `run` rebind it; `run` only accepts `ToSocketAddrs`, not
+        // an already-bound listener.
+        let server_addr = StdTcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        let server_config = test_server_config();
+        thread::spawn(move || {
+            run(
+                server_addr,
+                server_config,
+                ServerMode::Forward(backend_port),
+            )
+            .unwrap();
+        });
+
+        let mut sock = loop {
+            match StdTcpStream::connect(server_addr) {
+                Ok(sock) => break sock,
+                Err(_) => thread::sleep(Duration::from_millis(5)),
+            }
+        };
+
+        let dns_name = webpki::DNSNameRef::try_from_ascii_str("localhost").unwrap();
+        let mut client_session = rustls::ClientSession::new(&test_client_config(), dns_name);
+        let mut stream = rustls::Stream::new(&mut client_session, &mut sock);
+
+        stream.write_all(b"ping").unwrap();
+
+        let mut buf = [0u8; 4];
+        stream.read_exact(&mut buf).unwrap();
+        assert_eq!(b"ping", &buf);
+
+        backend_thread.join().unwrap();
+    }
+}